@@ -1,5 +1,11 @@
-use std::{collections::HashSet, io::stdin};
+use std::{
+    collections::{HashMap, VecDeque},
+    env::args,
+    io::stdin,
+    iter::from_fn,
+};
 
+use rayon::prelude::*;
 use regex_macro::regex;
 
 enum Component {
@@ -12,141 +18,528 @@ struct Point {
     y: usize,
 }
 
+/// The direction in which an element's cells run from its first cell.
+#[derive(Clone, Copy, PartialEq)]
+enum Orientation {
+    /// Left to right.
+    Horizontal,
+    /// Top to bottom.
+    Vertical,
+    /// Top-left to bottom-right.
+    Diagonal,
+    /// Top-right to bottom-left.
+    AntiDiagonal,
+}
+
 struct Element {
     component: Component,
     position: Point,
     length: usize,
+    orientation: Orientation,
+}
+
+/// The rule used to decide which cells are connected to one another.
+#[derive(Clone, Copy)]
+enum Adjacency {
+    /// Orthogonal neighbours only (4-neighbour).
+    VonNeumann,
+    /// Orthogonal and diagonal neighbours (8-neighbour).
+    Moore,
+    /// Every cell within `r` steps in any direction, diagonals included.
+    Chebyshev(usize),
+}
+
+/// A part number or gear ratio, reported as soon as it can be decided.
+enum Decision {
+    PartNumber(u32),
+    GearRatio(u32),
+}
+
+struct Options {
+    adjacency: Adjacency,
+    wrap: bool,
+    stream: bool,
+    orientations: Vec<Orientation>,
 }
 
+/// Marks a cell of the index grid that isn't covered by any element.
+const EMPTY: u32 = u32::MAX;
+
+/// The fewest elements worth finding parts among in parallel. Smaller
+/// schematics, like the windows used when streaming, are evaluated on the
+/// calling thread.
+const PARALLEL_ELEMENTS: usize = 4096;
+
 struct Schematic<'a> {
-    grid: Vec<Option<&'a Element>>,
+    elements: Vec<&'a Element>,
+    /// For each cell, the index of the element covering it (or [`EMPTY`]).
+    grid: Vec<u32>,
+    /// For each element, whether it's a part number.
+    parts: Vec<bool>,
     width: usize,
+    top: usize,
     height: usize,
+    offsets: Vec<(isize, isize)>,
+    wrap: bool,
 }
 
 fn main() {
-    let mut lines = stdin().lines().map(|l| l.unwrap()).peekable();
+    let options = parse_args(args().skip(1));
+
+    if options.stream {
+        assert!(!options.wrap, "a wrapping schematic can't be streamed");
+        assert!(
+            options.orientations == [Orientation::Horizontal],
+            "numbers can only be read left-to-right when streaming"
+        );
+
+        let mut sum_of_part_numbers = 0;
+        let mut sum_of_gear_ratios = 0;
+
+        for decision in evaluate_streaming(stdin().lines().map(|l| l.unwrap()), options.adjacency) {
+            match decision {
+                Decision::PartNumber(value) => {
+                    println!("part {}", value);
+                    sum_of_part_numbers += value;
+                }
+                Decision::GearRatio(value) => {
+                    println!("gear {}", value);
+                    sum_of_gear_ratios += value;
+                }
+            }
+        }
+
+        println!("{}", sum_of_part_numbers);
+        println!("{}", sum_of_gear_ratios);
+        return;
+    }
 
-    let width = lines.peek().map_or(0, |l| l.len());
-    let elements = find_elements(lines).collect::<Vec<_>>();
-    let schematic = Schematic::from_elements(&elements, width);
+    let lines = stdin().lines().map(|l| l.unwrap()).collect::<Vec<_>>();
+
+    let width = lines.first().map_or(0, |l| l.len());
+    let height = lines.len();
+    let elements = find_elements(lines, &options.orientations);
+    let schematic =
+        Schematic::from_elements(&elements, width, 0, height, options.adjacency, options.wrap);
 
     // part 1
-    let sum_of_part_numbers: u32 = elements
-        .iter()
-        .filter_map(|e| schematic.get_part_number(e))
+    let sum_of_part_numbers: u32 = (0..schematic.len())
+        .into_par_iter()
+        .filter_map(|i| schematic.get_part_number(i))
         .sum();
 
     // part 2
-    let sum_of_gear_ratios: u32 = elements
-        .iter()
-        .filter_map(|e| schematic.get_gear_ratio(e))
+    let sum_of_gear_ratios: u32 = (0..schematic.len())
+        .into_par_iter()
+        .filter_map(|i| schematic.get_gear_ratio(i))
         .sum();
 
     println!("{}", sum_of_part_numbers);
     println!("{}", sum_of_gear_ratios);
 }
 
-fn find_elements<I>(lines: I) -> impl Iterator<Item = Element>
+/// Reads the adjacency model (`--adjacency 4|8|chebyshev:<r>`), whether the
+/// schematic wraps around at its edges (`--wrap`), whether results should be
+/// streamed as they're decided (`--stream`), and whether numbers may also be
+/// read top-to-bottom (`--vertical`) or along diagonals (`--diagonal`).
+fn parse_args<I>(args: I) -> Options
 where
     I: IntoIterator<Item = String>,
 {
+    let mut adjacency = Adjacency::Moore;
+    let mut wrap = false;
+    let mut stream = false;
+    let mut vertical = false;
+    let mut diagonal = false;
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--adjacency" => {
+                let model = args.next().expect("missing adjacency model");
+
+                adjacency = match model.as_str() {
+                    "4" => Adjacency::VonNeumann,
+                    "8" => Adjacency::Moore,
+                    _ => match model.strip_prefix("chebyshev:") {
+                        Some(r) => Adjacency::Chebyshev(r.parse().expect("bad radius")),
+                        None => panic!("unknown adjacency model: {}", model),
+                    },
+                };
+            }
+            "--wrap" => wrap = true,
+            "--stream" => stream = true,
+            "--vertical" => vertical = true,
+            "--diagonal" => diagonal = true,
+            _ => panic!("unknown argument: {}", arg),
+        }
+    }
+
+    let mut orientations = vec![Orientation::Horizontal];
+
+    if vertical {
+        orientations.push(Orientation::Vertical);
+    }
+
+    if diagonal {
+        orientations.extend([Orientation::Diagonal, Orientation::AntiDiagonal]);
+    }
+
+    Options {
+        adjacency,
+        wrap,
+        stream,
+        orientations,
+    }
+}
+
+/// Finds all elements in the schematic. Numbers are read left-to-right first;
+/// digits left on their own are then joined into numbers along each of the
+/// other `orientations`, in order.
+fn find_elements<I>(lines: I, orientations: &[Orientation]) -> Vec<Element>
+where
+    I: IntoIterator<Item = String>,
+{
+    let (mut elements, singles): (Vec<_>, Vec<_>) = lines
+        .into_iter()
+        .enumerate()
+        .flat_map(|(y, line)| find_row_elements(y, &line))
+        .partition(|e| e.length > 1 || matches!(e.component, Component::Symbol(_)));
+
+    let mut digits = singles
+        .into_iter()
+        .map(|e| ((e.position.x, e.position.y), e))
+        .collect::<HashMap<_, _>>();
+
+    for &orientation in orientations {
+        if orientation == Orientation::Horizontal {
+            continue;
+        }
+
+        let (dx, dy) = orientation.step();
+        let mut starts = digits
+            .keys()
+            .filter(|&&(x, y)| {
+                let previous = (x.wrapping_add_signed(-dx), y.wrapping_sub(dy as usize));
+                !digits.contains_key(&previous)
+            })
+            .copied()
+            .collect::<Vec<_>>();
+
+        starts.sort_by_key(|&(x, y)| (y, x));
+
+        for (x, y) in starts {
+            let mut run = vec![(x, y)];
+
+            loop {
+                let &(x, y) = run.last().unwrap();
+                let next = (x.wrapping_add_signed(dx), y + dy as usize);
+
+                if !digits.contains_key(&next) {
+                    break;
+                }
+
+                run.push(next);
+            }
+
+            if run.len() < 2 {
+                continue;
+            }
+
+            let value = run
+                .iter()
+                .try_fold(0u32, |value, position| match digits[position].component {
+                    Component::Number(digit) => value.checked_mul(10)?.checked_add(digit),
+                    Component::Symbol(_) => unreachable!(),
+                })
+                .unwrap_or_else(|| panic!("{}", too_large(x, y)));
+
+            for position in &run {
+                digits.remove(position);
+            }
+
+            elements.push(Element {
+                component: Component::Number(value),
+                position: Point { x, y },
+                length: run.len(),
+                orientation,
+            });
+        }
+    }
+
+    elements.extend(digits.into_values());
+    elements.sort_by_key(|e| (e.position.y, e.position.x));
+    elements
+}
+
+fn find_row_elements(y: usize, line: &str) -> Vec<Element> {
     let element_pattern = regex!(r"(\d+|[^.])");
 
-    lines.into_iter().enumerate().flat_map(|(y, line)| {
-        element_pattern
-            .find_iter(&line)
-            .map(|m| Element {
-                component: match u32::from_str_radix(m.as_str(), 10) {
-                    Ok(number) => Component::Number(number),
-                    Err(_) => Component::Symbol(m.as_str().chars().nth(0).unwrap()),
-                },
-                position: Point { x: m.start(), y },
-                length: m.len(),
+    element_pattern
+        .find_iter(line)
+        .map(|m| Element {
+            component: match m.as_str().chars().next().unwrap() {
+                c if c.is_ascii_digit() => Component::Number(
+                    m.as_str()
+                        .parse()
+                        .unwrap_or_else(|_| panic!("{}", too_large(m.start(), y))),
+                ),
+                c => Component::Symbol(c),
+            },
+            position: Point { x: m.start(), y },
+            length: m.len(),
+            orientation: Orientation::Horizontal,
+        })
+        .collect()
+}
+
+/// Describes a number starting at `x`, `y` that doesn't fit in a `u32`.
+fn too_large(x: usize, y: usize) -> String {
+    format!("number at line {}, column {} is too large", y + 1, x + 1)
+}
+
+/// Evaluates the schematic a window of rows at a time, yielding each part
+/// number and gear ratio once every row within reach of it has been read.
+/// Only the rows within reach of the row being evaluated are kept in memory
+/// (three rows for the 4- and 8-neighbour models).
+fn evaluate_streaming<I>(lines: I, adjacency: Adjacency) -> impl Iterator<Item = Decision>
+where
+    I: IntoIterator<Item = String>,
+{
+    let reach = adjacency.reach();
+    let mut lines = lines.into_iter().enumerate();
+    let mut window = VecDeque::<Vec<Element>>::new();
+    let mut width = 0;
+    let mut top = 0;
+    let mut center = 0;
+
+    from_fn(move || {
+        while top + window.len() <= center + reach {
+            match lines.next() {
+                Some((y, line)) => {
+                    if y == 0 {
+                        width = line.len();
+                    }
+
+                    window.push_back(find_row_elements(y, &line));
+                }
+                None => break,
+            }
+        }
+
+        if center >= top + window.len() {
+            return None;
+        }
+
+        let schematic = Schematic::from_elements(
+            window.iter().flatten(),
+            width,
+            top,
+            window.len(),
+            adjacency,
+            false,
+        );
+
+        // any number adjacent to a gear is adjacent to a symbol, so the
+        // gear's ratio can be decided without looking past the window:
+        let start = window
+            .iter()
+            .take(center - top)
+            .map(Vec::len)
+            .sum::<usize>();
+        let decisions = (start..(start + window[center - top].len()))
+            .filter_map(|i| {
+                schematic
+                    .get_part_number(i)
+                    .map(Decision::PartNumber)
+                    .or_else(|| schematic.get_gear_ratio(i).map(Decision::GearRatio))
             })
-            .collect::<Vec<_>>()
+            .collect::<Vec<_>>();
+
+        center += 1;
+
+        while top + reach < center {
+            window.pop_front();
+            top += 1;
+        }
+
+        Some(decisions)
     })
+    .flatten()
 }
 
-impl<'a> Schematic<'a> {
-    /// Builds a schematic containing the provided elements.
-    fn from_elements(elements: &'a [Element], width: usize) -> Self {
-        let height = elements.iter().map(|e| e.position.y + 1).max().unwrap_or(0);
-        let mut grid = vec![None; width * height];
+impl Adjacency {
+    /// Returns the offsets from a cell to each of the cells adjacent to it.
+    fn offsets(self) -> Vec<(isize, isize)> {
+        let radius = match self {
+            Adjacency::VonNeumann => return vec![(0, -1), (-1, 0), (1, 0), (0, 1)],
+            Adjacency::Moore => 1,
+            Adjacency::Chebyshev(r) => r as isize,
+        };
 
-        for element in elements {
-            let start = ((width * element.position.y) + element.position.x) as usize;
+        (-radius..=radius)
+            .flat_map(|dy| (-radius..=radius).map(move |dx| (dx, dy)))
+            .filter(|&offset| offset != (0, 0))
+            .collect()
+    }
 
-            for i in start..(start + element.length) {
-                grid[i] = Some(element);
+    /// Returns the furthest number of rows away an adjacent cell can be.
+    fn reach(self) -> usize {
+        match self {
+            Adjacency::VonNeumann | Adjacency::Moore => 1,
+            Adjacency::Chebyshev(r) => r,
+        }
+    }
+}
+
+impl Orientation {
+    /// Returns the offset from each of an element's cells to the next.
+    fn step(self) -> (isize, isize) {
+        match self {
+            Orientation::Horizontal => (1, 0),
+            Orientation::Vertical => (0, 1),
+            Orientation::Diagonal => (1, 1),
+            Orientation::AntiDiagonal => (-1, 1),
+        }
+    }
+}
+
+impl Element {
+    /// Returns the positions of all cells covered by the element.
+    fn cells(&self) -> impl Iterator<Item = Point> + '_ {
+        let (dx, dy) = self.orientation.step();
+
+        (0..self.length).map(move |i| Point {
+            x: self.position.x.wrapping_add_signed(dx * i as isize),
+            y: self.position.y + (dy as usize * i),
+        })
+    }
+}
+
+impl<'a> Schematic<'a> {
+    /// Builds a schematic containing the provided elements, connected
+    /// according to `adjacency`. The schematic covers `height` rows starting
+    /// at row `top`.
+    fn from_elements<I>(
+        elements: I,
+        width: usize,
+        top: usize,
+        height: usize,
+        adjacency: Adjacency,
+        wrap: bool,
+    ) -> Self
+    where
+        I: IntoIterator<Item = &'a Element>,
+    {
+        let elements = elements.into_iter().collect::<Vec<_>>();
+        let mut grid = vec![EMPTY; width * height];
+
+        for (index, element) in elements.iter().enumerate() {
+            for cell in element.cells() {
+                grid[(width * (cell.y - top)) + cell.x] = index as u32;
             }
         }
 
-        Self {
+        let mut schematic = Self {
+            elements,
             grid,
+            parts: Vec::new(),
             width,
+            top,
             height,
-        }
-    }
+            offsets: adjacency.offsets(),
+            wrap,
+        };
 
-    /// Returns all elements in the schematic that are adjacent (including
-    /// diagonally) to the provided element.
-    fn find_adjacent_elements(&self, element: &'a Element) -> impl Iterator<Item = &Element> {
-        let min = Point {
-            x: element.position.x.max(1) - 1,
-            y: element.position.y.max(1) - 1,
+        let is_part = |index: usize| {
+            matches!(schematic.elements[index].component, Component::Number(_))
+                && schematic
+                    .find_adjacent_elements(index)
+                    .any(|i| matches!(schematic.elements[i].component, Component::Symbol(_)))
         };
 
-        let max = Point {
-            x: (element.position.x + element.length).min(self.width - 2) + 1,
-            y: element.position.y.min(self.height - 2) + 1,
+        let parts = if schematic.len() < PARALLEL_ELEMENTS {
+            (0..schematic.len()).map(is_part).collect()
+        } else {
+            (0..schematic.len()).into_par_iter().map(is_part).collect()
         };
 
-        (min.y..=max.y).flat_map(move |y| {
-            let row_start = y * self.width;
-            let row = &self.grid[row_start..(row_start + self.width)];
+        schematic.parts = parts;
 
-            // avoid returning dupes:
-            let mut seen = HashSet::new();
+        schematic
+    }
 
-            // prevent element from appearing in its own results:
-            if y == element.position.y {
-                seen.insert(element.position.x);
-            }
+    /// Returns the number of elements in the schematic.
+    fn len(&self) -> usize {
+        self.elements.len()
+    }
 
-            row[min.x..max.x]
-                .iter()
-                .filter_map(|&pointer| pointer)
-                .filter(move |&pointer| seen.insert(pointer.position.x))
-        })
+    /// Returns the cell `offset` away from `cell`, wrapping around the edges
+    /// if the schematic is toroidal.
+    fn step(&self, cell: &Point, (dx, dy): (isize, isize)) -> Option<Point> {
+        let x = cell.x as isize + dx;
+        let y = cell.y as isize + dy;
+
+        if self.wrap {
+            Some(Point {
+                x: x.rem_euclid(self.width as isize) as usize,
+                y: y.rem_euclid(self.height as isize) as usize,
+            })
+        } else if (0..self.width as isize).contains(&x)
+            && (self.top as isize..(self.top + self.height) as isize).contains(&y)
+        {
+            Some(Point {
+                x: x as usize,
+                y: y as usize,
+            })
+        } else {
+            None
+        }
     }
 
-    /// If `element` is a part (adjacent to a symbol), return its value.
-    fn get_part_number(&self, element: &Element) -> Option<u32> {
-        match element.component {
-            Component::Number(value)
-                if self
-                    .find_adjacent_elements(element)
-                    .any(|e| matches!(e.component, Component::Symbol(_))) =>
-            {
-                Some(value)
-            }
+    /// Returns the indexes of all elements in the schematic that are adjacent
+    /// to the element at `index` under the schematic's adjacency model. An
+    /// element may be returned more than once.
+    fn find_adjacent_elements(&self, index: usize) -> impl Iterator<Item = usize> + '_ {
+        self.elements[index]
+            .cells()
+            .flat_map(move |cell| {
+                self.offsets
+                    .iter()
+                    .filter_map(move |&offset| self.step(&cell, offset))
+            })
+            .map(|cell| self.grid[((cell.y - self.top) * self.width) + cell.x])
+            .filter(move |&i| i != EMPTY && i as usize != index)
+            .map(|i| i as usize)
+    }
+
+    /// If the element at `index` is a part (adjacent to a symbol), return its
+    /// value.
+    fn get_part_number(&self, index: usize) -> Option<u32> {
+        match self.elements[index].component {
+            Component::Number(value) if self.parts[index] => Some(value),
             _ => None,
         }
     }
 
-    /// If `element` is a gear (adjacent to exactly two parts), return the
-    /// product of the parts.
-    fn get_gear_ratio(&self, element: &Element) -> Option<u32> {
-        match element.component {
+    /// If the element at `index` is a gear (adjacent to exactly two parts),
+    /// return the product of the parts.
+    fn get_gear_ratio(&self, index: usize) -> Option<u32> {
+        match self.elements[index].component {
             Component::Symbol('*') => {
-                let adjacent_part_numbers = self
-                    .find_adjacent_elements(element)
-                    .filter_map(|e| self.get_part_number(e))
+                let mut adjacent_parts = self
+                    .find_adjacent_elements(index)
+                    .filter(|&i| self.parts[i])
                     .collect::<Vec<_>>();
 
-                if adjacent_part_numbers.len() == 2 {
-                    Some(adjacent_part_numbers.iter().product())
+                adjacent_parts.sort_unstable();
+                adjacent_parts.dedup();
+
+                if adjacent_parts.len() == 2 {
+                    adjacent_parts
+                        .iter()
+                        .map(|&i| self.get_part_number(i).unwrap())
+                        .reduce(|a, b| a * b)
                 } else {
                     None
                 }
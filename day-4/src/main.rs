@@ -1,68 +1,924 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashSet},
+    env::args,
+    fmt::{self, Display, Formatter},
+    fs::read_to_string,
     io::stdin,
+    iter::zip,
+    ops::RangeInclusive,
+    process::exit,
 };
 
 use regex_macro::regex;
 
+/// A set of card numbers, stored as a bitset with one bit per number. Only
+/// the words up to the highest number in the set are allocated: a fixed set
+/// covering every `u16` would take 8 KiB, and with two per card and every
+/// card held in memory, millions of cards wouldn't fit. Card numbers are
+/// usually small, so this is a single word for most cards.
+struct NumberSet {
+    words: Vec<u64>,
+}
+
 struct Card {
     id: u32,
-    numbers: HashSet<u8>,
-    winners: HashSet<u8>,
+    numbers: NumberSet,
+    winners: NumberSet,
+    /// The number of times each number held more than once appears.
+    repeated_numbers: BTreeMap<u16, usize>,
+    /// The number of times each winning number listed more than once appears.
+    repeated_winners: BTreeMap<u16, usize>,
+}
+
+/// A number listed more than once on a card.
+struct DuplicateNumber {
+    id: u32,
+    number: u16,
+    count: usize,
+    winning: bool,
+}
+
+/// A problem with the sequence of card ids, which are expected to run from 1
+/// upwards without gaps.
+enum IdError {
+    OutOfOrder {
+        id: u32,
+        previous: u32,
+    },
+    Duplicate(u32),
+    Zero,
+    /// A run of consecutive ids that no card has.
+    Missing(RangeInclusive<u32>),
+}
+
+/// How copies are counted.
+#[derive(Clone, Copy)]
+enum Counting {
+    /// Exact counts, failing if they grow too large.
+    Exact,
+    /// Counts modulo the provided value.
+    Modulo(u128),
+}
+
+/// A copy count or point total that grew too large to be counted exactly.
+enum Overflow {
+    Card(u32),
+    Total,
+    Points(String),
+    Yield(u32),
+}
+
+/// A rule for turning the number of winning numbers on a card into points.
+trait ScoringRule {
+    /// Returns the points for a card with `winning_count` winning numbers, or
+    /// `None` if they're too large to count.
+    fn score(&self, winning_count: usize) -> Option<u128>;
+}
+
+/// Scores `first` points for the first winning number, then multiplies by
+/// `ratio` for each one after (1, 2, 4, 8... when doubling).
+struct Geometric {
+    first: u128,
+    ratio: u128,
+}
+
+/// Scores `per_match` points for each winning number.
+struct Linear {
+    per_match: u128,
+}
+
+/// Scores along the Fibonacci sequence (1, 2, 3, 5, 8...).
+struct Fibonacci;
+
+/// Scores `points[n - 1]` for `n` winning numbers, or the last entry if there
+/// are more winning numbers than entries.
+struct Table {
+    points: Vec<u128>,
+}
+
+/// A format for the per-card report.
+enum ReportFormat {
+    Csv,
+    Json,
+}
+
+/// A row of the per-card report.
+struct CardReport {
+    id: u32,
+    matches: usize,
+    /// The points scored under each rule.
+    points: Vec<u128>,
+    copies_won: u128,
+    instances: u128,
+}
+
+/// Where each card's copies came from.
+struct Provenance<'a> {
+    cards: &'a [Card],
+    instances: &'a [u128],
+    /// For each card, the index of each earlier card that won copies of it,
+    /// along with the number of copies won.
+    sources: Vec<Vec<(usize, u128)>>,
+}
+
+/// What the program prints.
+enum Output {
+    /// The point and copy totals.
+    Totals,
+    /// A per-card report.
+    Report(ReportFormat),
+    /// The cards that won copies of a card, down to a given depth.
+    Why { id: u32, depth: usize },
+    /// The provenance of every card's copies as a DOT graph.
+    Dot,
+    /// The number of cards that one original of each card ultimately
+    /// produces.
+    Yields(Vec<u32>),
+}
+
+struct Options {
+    counting: Counting,
+    rules: Vec<(String, Box<dyn ScoringRule>)>,
+    output: Output,
+    check_duplicates: bool,
+    multiset: bool,
 }
 
 fn main() {
-    let mut points = 0;
-    let mut copy_counts = HashMap::new();
+    let options = parse_args(args().skip(1));
+
+    let cards = stdin()
+        .lines()
+        .map(|l| parse_card(&l.unwrap()))
+        .collect::<Vec<_>>();
+
+    let errors = validate_ids(&cards);
+    let duplicates = if options.check_duplicates {
+        find_duplicates(&cards)
+    } else {
+        Vec::new()
+    };
+
+    if !errors.is_empty() || !duplicates.is_empty() {
+        for error in errors {
+            eprintln!("{}", error);
+        }
+
+        for duplicate in duplicates {
+            eprintln!("{}", duplicate);
+        }
+
+        exit(1);
+    }
+
+    let winning_counts = cards
+        .iter()
+        .map(|c| c.winning_count(options.multiset))
+        .collect::<Vec<_>>();
+
+    // yields only depend on the cards after the ones asked about, so nothing
+    // else needs counting (or can overflow) first:
+    if let Output::Yields(ids) = &options.output {
+        let indexes = ids
+            .iter()
+            .map(|&id| find_card(&cards, id))
+            .collect::<Vec<_>>();
+        let first = *indexes.iter().min().unwrap();
+        let yields = calculate_yields(&winning_counts[first..], options.counting);
+
+        for (&id, index) in zip(ids, indexes) {
+            let y = or_exit(yields[index - first].ok_or(Overflow::Yield(id)));
+            println!("{}\t{}", id, y);
+        }
+
+        return;
+    }
+
+    // part 1
+    let scores = or_exit(
+        options
+            .rules
+            .iter()
+            .map(|(name, rule)| {
+                winning_counts
+                    .iter()
+                    .map(|&w| rule.score(w))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| Overflow::Points(name.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>(),
+    );
+
+    let points = or_exit(
+        zip(&options.rules, &scores)
+            .map(|((name, _), scores)| {
+                scores
+                    .iter()
+                    .try_fold(0u128, |total, &s| total.checked_add(s))
+                    .ok_or_else(|| Overflow::Points(name.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>(),
+    );
+
+    // part 2
+    let copy_counts = or_exit(count_copies(&cards, &winning_counts, options.counting));
+
+    if let Output::Report(format) = options.output {
+        let rows = cards
+            .iter()
+            .enumerate()
+            .map(|(index, card)| CardReport {
+                id: card.id,
+                matches: winning_counts[index],
+                points: scores.iter().map(|s| s[index]).collect(),
+                copies_won: options.counting.sub_one(copy_counts[index]),
+                instances: copy_counts[index],
+            })
+            .collect::<Vec<_>>();
+
+        let columns = if let [_] = &options.rules[..] {
+            vec!["points".to_string()]
+        } else {
+            options
+                .rules
+                .iter()
+                .map(|(name, _)| format!("points:{}", name))
+                .collect()
+        };
+
+        match format {
+            ReportFormat::Csv => print_csv_report(&columns, &rows),
+            ReportFormat::Json => print_json_report(&columns, &rows),
+        }
+
+        return;
+    }
 
-    for line in stdin().lines().map(|l| l.unwrap()) {
-        let card = parse_card(&line);
-        let winning_count = card.numbers.intersection(&card.winners).count() as u32;
+    if let Output::Why { id, depth } = options.output {
+        let index = find_card(&cards, id);
 
-        // part 1
-        points += calculate_score(winning_count);
+        Provenance::build(&cards, &winning_counts, &copy_counts).print_trace(index, depth);
+        return;
+    }
+
+    if let Output::Dot = options.output {
+        Provenance::build(&cards, &winning_counts, &copy_counts).print_dot();
+        return;
+    }
+
+    if let [points] = &points[..] {
+        println!("{}", points);
+    } else {
+        for ((name, _), points) in zip(&options.rules, points) {
+            println!("{}\t{}", name, points);
+        }
+    }
+
+    let total_copies = or_exit(copy_counts.iter().try_fold(0, |total, &count| {
+        options.counting.add(total, count).ok_or(Overflow::Total)
+    }));
+
+    println!("{}", total_copies);
+}
+
+/// Unwraps `result`, or prints the error and exits if there is one.
+fn or_exit<T, E>(result: Result<T, E>) -> T
+where
+    E: Display,
+{
+    result.unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        exit(1);
+    })
+}
+
+/// Reads how copies should be counted (`--modulo <m>` to count modulo `m`)
+/// and the rules to score cards under (`--scoring <rule>`, or
+/// `--scoring-config <path>` for a file of `<name> = <rule>` lines), which
+/// default to doubling. Numbers listed more than once on a card can be
+/// reported as errors (`--check-duplicates`) or counted as separate matches
+/// (`--multiset`).
+///
+/// Instead of the totals, the program can print a per-card report
+/// (`--report csv|json`), the cards that won copies of a card (`--why <id>`,
+/// optionally followed by `--depth <n>` to recurse), a graph of where every
+/// card's copies came from (`--dot`), or how many cards one original of a
+/// card ultimately produces (`--yield <id>`, repeatable).
+fn parse_args<I>(args: I) -> Options
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut counting = Counting::Exact;
+    let mut rules = Vec::new();
+    let mut output = Output::Totals;
+    let mut check_duplicates = false;
+    let mut multiset = false;
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--modulo" => {
+                let modulus: u64 = args
+                    .next()
+                    .and_then(|m| m.parse().ok())
+                    .filter(|&m| m > 0)
+                    .expect("modulus must be a positive integer");
 
-        // part 2
-        let copy_count = copy_counts
-            .entry(card.id)
-            .and_modify(|c| *c += 1)
-            .or_insert(1)
-            .clone();
+                counting = Counting::Modulo(modulus as u128);
+            }
+            "--scoring" => {
+                let spec = args.next().expect("missing scoring rule");
+                rules.push((spec.clone(), parse_rule(&spec)));
+            }
+            "--scoring-config" => {
+                let path = args.next().expect("missing scoring config path");
+                let config =
+                    read_to_string(&path).unwrap_or_else(|e| panic!("can't read {}: {}", path, e));
 
-        for id in (card.id + 1)..=(card.id + winning_count) {
-            copy_counts
-                .entry(id)
-                .and_modify(|c| *c += copy_count)
-                .or_insert(copy_count);
+                rules.extend(parse_rule_config(&config));
+            }
+            "--report" => {
+                output = Output::Report(match args.next().as_deref() {
+                    Some("csv") => ReportFormat::Csv,
+                    Some("json") => ReportFormat::Json,
+                    _ => panic!("report format must be csv or json"),
+                });
+            }
+            "--why" => {
+                let id = args
+                    .next()
+                    .and_then(|id| id.parse().ok())
+                    .expect("missing card id");
+
+                output = Output::Why { id, depth: 1 };
+            }
+            "--depth" => match output {
+                Output::Why { ref mut depth, .. } => {
+                    *depth = args
+                        .next()
+                        .and_then(|d| d.parse().ok())
+                        .expect("depth must be a non-negative integer");
+                }
+                _ => panic!("--depth must follow --why"),
+            },
+            "--dot" => output = Output::Dot,
+            "--yield" => {
+                let id = args
+                    .next()
+                    .and_then(|id| id.parse().ok())
+                    .expect("missing card id");
+
+                match output {
+                    Output::Yields(ref mut ids) => ids.push(id),
+                    _ => output = Output::Yields(vec![id]),
+                }
+            }
+            "--check-duplicates" => check_duplicates = true,
+            "--multiset" => multiset = true,
+            _ => panic!("unknown argument: {}", arg),
         }
     }
 
-    println!("{}", points);
-    println!("{}", copy_counts.values().sum::<u32>());
+    if rules.is_empty() {
+        rules.push(("doubling".to_string(), parse_rule("doubling")));
+    }
+
+    Options {
+        counting,
+        rules,
+        output,
+        check_duplicates,
+        multiset,
+    }
+}
+
+/// Parses a scoring rule: `doubling`, `geometric:<first>:<ratio>`,
+/// `linear[:<per match>]`, `fibonacci` or `table:<points>,<points>,...`.
+fn parse_rule(spec: &str) -> Box<dyn ScoringRule> {
+    fn parse_points(str: &str) -> u128 {
+        str.trim()
+            .parse()
+            .unwrap_or_else(|_| panic!("bad points: {}", str))
+    }
+
+    let (kind, parameters) = spec.split_once(':').unwrap_or((spec, ""));
+
+    match (kind, parameters) {
+        ("doubling", "") => Box::new(Geometric { first: 1, ratio: 2 }),
+        ("geometric", _) => {
+            let (first, ratio) = parameters
+                .split_once(':')
+                .unwrap_or_else(|| panic!("bad geometric rule: {}", spec));
+
+            Box::new(Geometric {
+                first: parse_points(first),
+                ratio: parse_points(ratio),
+            })
+        }
+        ("linear", "") => Box::new(Linear { per_match: 1 }),
+        ("linear", _) => Box::new(Linear {
+            per_match: parse_points(parameters),
+        }),
+        ("fibonacci", "") => Box::new(Fibonacci),
+        ("table", _) => Box::new(Table {
+            points: parameters.split(',').map(parse_points).collect(),
+        }),
+        _ => panic!("unknown scoring rule: {}", spec),
+    }
+}
+
+/// Parses named scoring rules, one `<name> = <rule>` per line. Blank lines
+/// and lines starting with `#` are ignored.
+fn parse_rule_config(config: &str) -> impl Iterator<Item = (String, Box<dyn ScoringRule>)> + '_ {
+    config
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|line| {
+            let (name, spec) = line
+                .split_once('=')
+                .unwrap_or_else(|| panic!("malformed scoring rule: {}", line));
+
+            (name.trim().to_string(), parse_rule(spec.trim()))
+        })
 }
 
 fn parse_card(str: &str) -> Card {
     let captures = regex!(r"Card\s*(?<id>\d+):\s*(?<winners>.+)\s*\|\s*(?<numbers>.+)")
         .captures(str)
-        .expect(&format!("malformed card: {}", str));
+        .unwrap_or_else(|| panic!("malformed card: {}", str));
+
+    let (numbers, repeated_numbers) = parse_numbers(captures.name("numbers").unwrap().as_str());
+    let (winners, repeated_winners) = parse_numbers(captures.name("winners").unwrap().as_str());
 
     Card {
-        id: u32::from_str_radix(captures.name("id").unwrap().as_str(), 10).unwrap(),
-        numbers: parse_numbers(captures.name("numbers").unwrap().as_str()),
-        winners: parse_numbers(captures.name("winners").unwrap().as_str()),
+        id: captures.name("id").unwrap().as_str().parse().unwrap(),
+        numbers,
+        winners,
+        repeated_numbers,
+        repeated_winners,
+    }
+}
+
+/// Parses a list of numbers into a set, along with the number of times each
+/// number listed more than once appears.
+fn parse_numbers(str: &str) -> (NumberSet, BTreeMap<u16, usize>) {
+    let mut set = NumberSet { words: Vec::new() };
+    let mut repeated = BTreeMap::new();
+
+    for s in str.split_whitespace() {
+        let number = s
+            .parse()
+            .unwrap_or_else(|_| panic!("bad number (must be 0 to 65535): {}", s));
+
+        if !set.insert(number) {
+            *repeated.entry(number).or_insert(1) += 1;
+        }
     }
+
+    (set, repeated)
 }
 
-fn parse_numbers(str: &str) -> HashSet<u8> {
-    str.split_whitespace()
-        .map(|s| u8::from_str_radix(s, 10).unwrap())
+/// Checks that card ids are unique, ascending and without gaps, returning
+/// every problem found.
+fn validate_ids(cards: &[Card]) -> Vec<IdError> {
+    let mut errors = Vec::new();
+    let mut seen = HashSet::new();
+    let mut previous = 0;
+
+    for card in cards {
+        if card.id == 0 {
+            errors.push(IdError::Zero);
+        } else if !seen.insert(card.id) {
+            errors.push(IdError::Duplicate(card.id));
+        } else if card.id < previous {
+            errors.push(IdError::OutOfOrder {
+                id: card.id,
+                previous,
+            });
+        }
+
+        previous = previous.max(card.id);
+    }
+
+    let mut ids = seen.into_iter().collect::<Vec<_>>();
+    ids.sort_unstable();
+
+    // every id in a gap between neighbouring ids is missing:
+    let mut next = 1;
+
+    for id in ids {
+        if id > next {
+            errors.push(IdError::Missing(next..=(id - 1)));
+        }
+
+        next = id.saturating_add(1);
+    }
+
+    errors
+}
+
+/// Returns every number listed more than once on a card, in either the
+/// winning numbers or the numbers held.
+fn find_duplicates(cards: &[Card]) -> Vec<DuplicateNumber> {
+    cards
+        .iter()
+        .flat_map(|card| {
+            let winners = card
+                .repeated_winners
+                .iter()
+                .map(|(&number, &count)| DuplicateNumber {
+                    id: card.id,
+                    number,
+                    count,
+                    winning: true,
+                });
+
+            let numbers = card
+                .repeated_numbers
+                .iter()
+                .map(|(&number, &count)| DuplicateNumber {
+                    id: card.id,
+                    number,
+                    count,
+                    winning: false,
+                });
+
+            winners.chain(numbers)
+        })
         .collect()
 }
 
-fn calculate_score(winning_count: u32) -> u32 {
-    if winning_count < 1 {
-        0
-    } else {
-        2u32.pow(winning_count - 1)
+/// Returns the index of the card with the provided id.
+fn find_card(cards: &[Card], id: u32) -> usize {
+    cards
+        .iter()
+        .position(|c| c.id == id)
+        .unwrap_or_else(|| panic!("no such card: {}", id))
+}
+
+/// Returns, for each card with the provided winning counts, the number of
+/// cards that one original of it ultimately produces (itself included), or
+/// `None` if that overflows. Cards are processed from last to first, so each
+/// card's yield is one plus the yields of the cards it wins.
+fn calculate_yields(winning_counts: &[usize], counting: Counting) -> Vec<Option<u128>> {
+    let mut yields = vec![Some(counting.one()); winning_counts.len()];
+
+    for index in (0..winning_counts.len()).rev() {
+        let end = (index + winning_counts[index]).min(winning_counts.len() - 1);
+
+        yields[index] = yields[(index + 1)..=end]
+            .iter()
+            .try_fold(counting.one(), |total, &y| counting.add(total, y?));
+    }
+
+    yields
+}
+
+/// Returns the number of instances of each card held once every won copy has
+/// been processed.
+fn count_copies(
+    cards: &[Card],
+    winning_counts: &[usize],
+    counting: Counting,
+) -> Result<Vec<u128>, Overflow> {
+    let mut copy_counts = vec![counting.one(); cards.len()];
+
+    for (index, &winning_count) in winning_counts.iter().enumerate() {
+        let copy_count = copy_counts[index];
+
+        // copies are only won for cards that exist:
+        let end = (index + winning_count).min(cards.len() - 1);
+
+        for (card, count) in cards[(index + 1)..=end]
+            .iter()
+            .zip(&mut copy_counts[(index + 1)..=end])
+        {
+            *count = counting
+                .add(*count, copy_count)
+                .ok_or(Overflow::Card(card.id))?;
+        }
+    }
+
+    Ok(copy_counts)
+}
+
+/// Prints the per-card report as CSV, with a points column for each of
+/// `columns`.
+fn print_csv_report(columns: &[String], rows: &[CardReport]) {
+    fn field(str: &str) -> String {
+        if str.contains([',', '"', '\n']) {
+            format!("\"{}\"", str.replace('"', "\"\""))
+        } else {
+            str.to_string()
+        }
+    }
+
+    let points_columns = columns.iter().map(|c| field(c)).collect::<Vec<_>>();
+    println!(
+        "id,matches,{},copies_won,instances",
+        points_columns.join(",")
+    );
+
+    for row in rows {
+        let points = row.points.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+
+        println!(
+            "{},{},{},{},{}",
+            row.id,
+            row.matches,
+            points.join(","),
+            row.copies_won,
+            row.instances
+        );
+    }
+}
+
+/// Prints the per-card report as a JSON array of objects, with a points
+/// property for each of `columns`.
+fn print_json_report(columns: &[String], rows: &[CardReport]) {
+    fn string(str: &str) -> String {
+        let mut escaped = String::from('"');
+
+        for c in str.chars() {
+            match c {
+                '"' => escaped.push_str("\\\""),
+                '\\' => escaped.push_str("\\\\"),
+                c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+                c => escaped.push(c),
+            }
+        }
+
+        escaped.push('"');
+        escaped
+    }
+
+    let objects = rows
+        .iter()
+        .map(|row| {
+            let points = zip(columns, &row.points)
+                .map(|(column, points)| format!("{}:{}", string(column), points))
+                .collect::<Vec<_>>();
+
+            format!(
+                "{{\"id\":{},\"matches\":{},{},\"copies_won\":{},\"instances\":{}}}",
+                row.id,
+                row.matches,
+                points.join(","),
+                row.copies_won,
+                row.instances
+            )
+        })
+        .collect::<Vec<_>>();
+
+    println!("[{}]", objects.join(","));
+}
+
+impl<'a> Provenance<'a> {
+    /// Works out where each card's copies came from, given the number of
+    /// instances of each card held.
+    fn build(cards: &'a [Card], winning_counts: &[usize], instances: &'a [u128]) -> Self {
+        let mut sources = vec![Vec::new(); cards.len()];
+
+        for (index, &winning_count) in winning_counts.iter().enumerate() {
+            let end = (index + winning_count).min(cards.len() - 1);
+
+            // every instance of the card wins one copy of each card after it:
+            for target in &mut sources[(index + 1)..=end] {
+                target.push((index, instances[index]));
+            }
+        }
+
+        Self {
+            cards,
+            instances,
+            sources,
+        }
+    }
+
+    /// Returns the index of each earlier card that won copies of the card at
+    /// `index`, along with the number of copies won.
+    fn sources(&self, index: usize) -> &[(usize, u128)] {
+        &self.sources[index]
+    }
+
+    /// Prints the cards that won copies of the card at `index`, followed by
+    /// the cards that won copies of those, and so on to `depth` levels.
+    fn print_trace(&self, index: usize, depth: usize) {
+        fn print_sources(provenance: &Provenance, index: usize, level: usize, depth: usize) {
+            if level > depth {
+                return;
+            }
+
+            for &(source, copies) in provenance.sources(index) {
+                println!(
+                    "{}card {}: {} copies",
+                    "  ".repeat(level),
+                    provenance.cards[source].id,
+                    copies
+                );
+
+                print_sources(provenance, source, level + 1, depth);
+            }
+        }
+
+        println!(
+            "card {}: {} instances",
+            self.cards[index].id, self.instances[index]
+        );
+
+        print_sources(self, index, 1, depth);
+    }
+
+    /// Prints a DOT graph with a node for each card and an edge from each card
+    /// to each card it won copies of.
+    fn print_dot(&self) {
+        println!("digraph provenance {{");
+
+        for (index, card) in self.cards.iter().enumerate() {
+            println!(
+                "    {} [label=\"card {}\\n{} instances\"];",
+                card.id, card.id, self.instances[index]
+            );
+        }
+
+        for (index, card) in self.cards.iter().enumerate() {
+            for &(source, copies) in self.sources(index) {
+                println!(
+                    "    {} -> {} [label=\"{}\"];",
+                    self.cards[source].id, card.id, copies
+                );
+            }
+        }
+
+        println!("}}");
+    }
+}
+
+impl Display for IdError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            IdError::OutOfOrder { id, previous } => {
+                write!(f, "card {} appears after card {}", id, previous)
+            }
+            IdError::Duplicate(id) => write!(f, "card {} appears more than once", id),
+            IdError::Zero => write!(f, "card 0 is out of range (ids start at 1)"),
+            IdError::Missing(ids) if ids.start() == ids.end() => {
+                write!(f, "card {} is missing", ids.start())
+            }
+            IdError::Missing(ids) => write!(f, "cards {:?} are missing", ids),
+        }
+    }
+}
+
+impl Card {
+    /// Returns the number of winning numbers held. When counting a multiset,
+    /// every pairing of a listed winning number with a held copy of it counts
+    /// separately; otherwise, each number counts once.
+    fn winning_count(&self, multiset: bool) -> usize {
+        let mut count = self.numbers.intersection_count(&self.winners);
+
+        if multiset {
+            for (&number, &held) in &self.repeated_numbers {
+                if self.winners.contains(number) {
+                    let listed = self.repeated_winners.get(&number).copied().unwrap_or(1);
+                    count += (held * listed) - 1;
+                }
+            }
+
+            for (&number, &listed) in &self.repeated_winners {
+                if self.numbers.contains(number) && !self.repeated_numbers.contains_key(&number) {
+                    count += listed - 1;
+                }
+            }
+        }
+
+        count
+    }
+}
+
+impl NumberSet {
+    /// Adds a number to the set, returning whether it wasn't already present.
+    fn insert(&mut self, number: u16) -> bool {
+        let index = number as usize / 64;
+        let bit = 1 << (number % 64);
+
+        if index >= self.words.len() {
+            self.words.resize(index + 1, 0);
+        }
+
+        let absent = self.words[index] & bit == 0;
+        self.words[index] |= bit;
+        absent
+    }
+
+    /// Returns whether the set contains a number.
+    fn contains(&self, number: u16) -> bool {
+        self.words
+            .get(number as usize / 64)
+            .is_some_and(|w| w & (1 << (number % 64)) != 0)
+    }
+
+    /// Returns the number of numbers in both sets.
+    fn intersection_count(&self, other: &NumberSet) -> usize {
+        zip(self.words.iter(), other.words.iter())
+            .map(|(a, b)| (a & b).count_ones() as usize)
+            .sum()
+    }
+}
+
+impl Counting {
+    /// Returns a count of one.
+    fn one(self) -> u128 {
+        match self {
+            Counting::Exact => 1,
+            Counting::Modulo(m) => 1 % m,
+        }
+    }
+
+    /// Subtracts one from a count of at least one.
+    fn sub_one(self, count: u128) -> u128 {
+        match self {
+            Counting::Exact => count - 1,
+            Counting::Modulo(m) => (count + m - 1) % m,
+        }
+    }
+
+    /// Adds two counts, returning `None` if the sum can't be counted exactly.
+    fn add(self, a: u128, b: u128) -> Option<u128> {
+        match self {
+            Counting::Exact => a.checked_add(b),
+            // the modulus fits in a u64, so the sum can't overflow:
+            Counting::Modulo(m) => Some((a + b) % m),
+        }
+    }
+}
+
+impl Display for DuplicateNumber {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "card {}: {} number {} appears {} times",
+            self.id,
+            if self.winning { "winning" } else { "held" },
+            self.number,
+            self.count
+        )
+    }
+}
+
+impl Display for Overflow {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Overflow::Card(id) => write!(f, "copies of card {} overflow; try --modulo", id),
+            Overflow::Total => write!(f, "total copies overflow; try --modulo"),
+            Overflow::Points(rule) => write!(f, "points under {} overflow", rule),
+            Overflow::Yield(id) => write!(f, "yield of card {} overflows; try --modulo", id),
+        }
+    }
+}
+
+impl ScoringRule for Geometric {
+    fn score(&self, winning_count: usize) -> Option<u128> {
+        if winning_count < 1 {
+            Some(0)
+        } else {
+            self.ratio
+                .checked_pow(u32::try_from(winning_count - 1).ok()?)?
+                .checked_mul(self.first)
+        }
+    }
+}
+
+impl ScoringRule for Linear {
+    fn score(&self, winning_count: usize) -> Option<u128> {
+        self.per_match.checked_mul(winning_count as u128)
+    }
+}
+
+impl ScoringRule for Fibonacci {
+    fn score(&self, winning_count: usize) -> Option<u128> {
+        if winning_count < 1 {
+            return Some(0);
+        }
+
+        let (mut a, mut b) = (1u128, 2u128);
+
+        for _ in 1..winning_count {
+            (a, b) = (b, a.checked_add(b)?);
+        }
+
+        Some(a)
+    }
+}
+
+impl ScoringRule for Table {
+    fn score(&self, winning_count: usize) -> Option<u128> {
+        if winning_count < 1 {
+            Some(0)
+        } else {
+            let index = (winning_count - 1).min(self.points.len() - 1);
+            Some(self.points[index])
+        }
     }
 }
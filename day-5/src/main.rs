@@ -1,125 +1,993 @@
-use std::io::stdin;
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    env::args,
+    fmt::{self, Display, Formatter},
+    io::stdin,
+    ops,
+    process::exit,
+    slice,
+};
 
 use regex_macro::regex;
 
 #[derive(Default)]
 struct Almanac {
     seeds: Vec<u64>,
-    seed_to_soil: Vec<Range>,
-    soil_to_fertilizer: Vec<Range>,
-    fertilizer_to_water: Vec<Range>,
-    water_to_light: Vec<Range>,
-    light_to_temperature: Vec<Range>,
-    temperature_to_humidity: Vec<Range>,
-    humidity_to_location: Vec<Range>,
+    /// The seeds, read as pairs of start and length.
+    seed_ranges: Vec<Interval>,
+    /// The maps in the almanac, keyed by source category.
+    maps: HashMap<String, Vec<Map>>,
 }
 
+/// Converts numbers in one category to numbers in another.
+struct Map {
+    source: String,
+    destination: String,
+    ranges: Vec<Range>,
+    /// The ranges, indexed by the source numbers they cover.
+    by_source: RangeIndex,
+    /// The ranges, indexed by the destination numbers they cover.
+    by_destination: RangeIndex,
+    /// The line of the almanac the map's header is on.
+    line: usize,
+}
+
+/// Sorted, non-overlapping intervals that can be binary searched for the
+/// range covering a number. Where ranges overlap, the one listed first in the
+/// map wins, just as it would when scanning the ranges in order.
+#[derive(Default)]
+struct RangeIndex {
+    /// Each interval, along with the index of the range covering it.
+    segments: Vec<(Interval, usize)>,
+}
+
+/// A range of a map. Ranges are checked to lie within the domain when
+/// they're parsed.
 struct Range {
     destination: u64,
     start: u64,
     length: u64,
+    /// The line of the almanac the range is on.
+    line: usize,
+}
+
+/// A likely mistake in an almanac.
+struct Lint {
+    line: usize,
+    message: String,
+}
+
+/// A half-open interval of numbers. The bounds are wider than the numbers
+/// themselves, so that an interval can include `u64::MAX`.
+type Interval = ops::Range<u128>;
+
+/// A piece of a piecewise-linear function, adding `offset` to every number in
+/// `source`.
+struct Piece {
+    source: Interval,
+    offset: i128,
+}
+
+/// A chain of maps collapsed into a single piecewise-linear function. The
+/// pieces are sorted and cover every number.
+struct Composition {
+    pieces: Vec<Piece>,
+}
+
+/// A format for printing a composition.
+enum Format {
+    Json,
+    Table,
+}
+
+/// What the program prints.
+enum Output {
+    /// The lowest locations for parts 1 and 2.
+    Answers,
+    /// The seed-to-location conversion as a single function.
+    Composition(Format),
+    /// Likely mistakes in the almanac.
+    Lints,
+    /// The number at every stage of the conversion of each query.
+    Traces(Vec<Query>),
+    /// The seeds from the almanac's seed ranges that land in the provided
+    /// locations.
+    Preimage(Interval),
+}
+
+/// A number to trace through the almanac.
+enum Query {
+    /// A number in the category converted from, traced forward.
+    Forward(u64),
+    /// A number in the category converted to, traced backward.
+    Backward(u64),
+}
+
+struct Options {
+    brute_force: bool,
+    output: Output,
+    /// The category to convert from.
+    from: String,
+    /// The category to convert to.
+    to: String,
+}
+
+/// Every number that can be converted.
+const DOMAIN: Interval = 0..(u64::MAX as u128 + 1);
+
+/// A problem with an almanac that prevents it from being used.
+struct ParseError {
+    line: usize,
+    message: String,
+}
+
+/// A reason that numbers in one category can't be converted to another.
+enum PathError {
+    Missing { from: String, to: String },
+    Ambiguous { from: String, to: String },
+    Cycle(String),
 }
 
 fn main() {
-    let almanac = parse_almanac(stdin().lines().map(|l| l.unwrap()));
+    let options = parse_args(args().skip(1));
+
+    let almanac = parse_almanac(stdin().lines().map(|l| l.unwrap())).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        exit(1);
+    });
+
+    if let Output::Lints = options.output {
+        let lints = lint(&almanac);
+
+        for lint in &lints {
+            println!("line {}: {}", lint.line, lint.message);
+        }
+
+        if !lints.is_empty() {
+            exit(1);
+        }
+
+        return;
+    }
+
+    let path = almanac
+        .path(&options.from, &options.to)
+        .unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            exit(1);
+        });
+
+    if let Output::Traces(queries) = &options.output {
+        for (i, query) in queries.iter().enumerate() {
+            if i > 0 {
+                println!();
+            }
+
+            trace(query, &options.from, &options.to, &path);
+        }
+
+        return;
+    }
+
+    if let Output::Preimage(locations) = &options.output {
+        let mut seeds = locations_to_seeds(slice::from_ref(locations), &path);
+
+        if options.from == "seed" {
+            seeds = intersect(&seeds, &merge_intervals(almanac.seed_ranges.clone()));
+        }
+
+        for interval in seeds {
+            println!("{:?}", interval);
+        }
+
+        return;
+    }
+
+    if let Output::Composition(format) = options.output {
+        let composition = Composition::compose(&path);
+
+        match format {
+            Format::Json => composition.print_json(),
+            Format::Table => composition.print_table(),
+        }
+
+        return;
+    }
 
     // part 1
-    let min_by_numbers = (0..u64::MAX)
-        .find(|&n| almanac.seeds.contains(&location_to_seed(n, &almanac)))
-        .unwrap_or_default();
+    let numbers = almanac
+        .seeds
+        .iter()
+        .map(|&s| (s as u128)..(s as u128 + 1))
+        .collect::<Vec<_>>();
+
+    // searching every location for one that maps back to a seed is slow, but
+    // useful for checking the interval arithmetic:
+    let min_by_numbers = if options.brute_force {
+        (0..=u64::MAX)
+            .find(|&n| almanac.seeds.contains(&location_to_seed(n, &path)))
+            .unwrap_or_default()
+    } else {
+        min_location(&numbers, &path)
+    };
 
     // part 2
-    let ranges = almanac.seeds.chunks_exact(2)
-        .map(|c| c[0]..(c[0] + c[1]))
-        .collect::<Vec<_>>();
+    let ranges = &almanac.seed_ranges;
 
-    let min_by_ranges = (0..u64::MAX)
-        .find(|&n| {
-            let seed = location_to_seed(n, &almanac);
-            ranges.iter().any(|r| r.contains(&seed))
-        })
-        .unwrap_or_default();
+    let min_by_ranges = if options.brute_force {
+        (0..=u64::MAX)
+            .find(|&n| {
+                let seed = location_to_seed(n, &path);
+                ranges.iter().any(|r| r.contains(&(seed as u128)))
+            })
+            .unwrap_or_default()
+    } else {
+        min_location(ranges, &path)
+    };
 
     println!("{}", min_by_numbers);
     println!("{}", min_by_ranges);
 }
 
-fn parse_almanac<I>(lines: I) -> Almanac
+/// Reads whether to search for answers by brute force (`--brute-force`), or
+/// whether to print the seed-to-location conversion as a single function
+/// (`--compose json|table`), likely mistakes in the almanac (`--lint`), or
+/// the conversion of particular seeds (`--trace-seed <n>`) or locations
+/// (`--trace-location <n>`) instead. Trace options can be repeated. It can
+/// also print which seeds in the seed ranges land in an interval of locations
+/// (`--seeds-for <start>..<end>`, or `..=<end>` to include the end).
+///
+/// Conversions run from seed to location unless other categories are chosen
+/// (`--from <category>` and `--to <category>`), in which case seeds and
+/// locations stand for numbers in those categories.
+fn parse_args<I>(args: I) -> Options
 where
     I: IntoIterator<Item = String>,
 {
+    let mut brute_force = false;
+    let mut output = Output::Answers;
+    let mut from = "seed".to_string();
+    let mut to = "location".to_string();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--brute-force" => brute_force = true,
+            "--compose" => {
+                output = Output::Composition(match args.next().as_deref() {
+                    Some("json") => Format::Json,
+                    Some("table") => Format::Table,
+                    _ => panic!("composition format must be json or table"),
+                });
+            }
+            "--lint" => output = Output::Lints,
+            "--trace-seed" | "--trace-location" => {
+                let n = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .expect("missing number to trace");
+
+                let query = if arg == "--trace-seed" {
+                    Query::Forward(n)
+                } else {
+                    Query::Backward(n)
+                };
+
+                match output {
+                    Output::Traces(ref mut queries) => queries.push(query),
+                    _ => output = Output::Traces(vec![query]),
+                }
+            }
+            "--from" => from = args.next().expect("missing category"),
+            "--to" => to = args.next().expect("missing category"),
+            "--seeds-for" => {
+                let locations = args.next().expect("missing locations");
+                output = Output::Preimage(
+                    parse_interval(&locations)
+                        .unwrap_or_else(|| panic!("bad locations: {}", locations)),
+                );
+            }
+            _ => panic!("unknown argument: {}", arg),
+        }
+    }
+
+    Options {
+        brute_force,
+        output,
+        from,
+        to,
+    }
+}
+
+/// Parses an interval written as `<start>..<end>` or `<start>..=<end>`,
+/// returning `None` if it's malformed or extends beyond the domain.
+fn parse_interval(str: &str) -> Option<Interval> {
+    let (start, end) = str.split_once("..")?;
+    let start = start.parse::<u64>().ok()? as u128;
+
+    let end = match end.strip_prefix('=') {
+        Some(end) => end.parse::<u64>().ok()? as u128 + 1,
+        None => end.parse().ok()?,
+    };
+
+    (end <= DOMAIN.end).then_some(start..end)
+}
+
+/// Parses an almanac, rejecting any numbers that can't be parsed, any seeds
+/// or ranges that extend beyond the domain, and any repeated maps.
+fn parse_almanac<I>(lines: I) -> Result<Almanac, ParseError>
+where
+    I: IntoIterator<Item = String>,
+{
+    fn parse_numbers(line: usize, str: &str) -> Result<Vec<u64>, ParseError> {
+        str.split_whitespace()
+            .map(|n| {
+                n.parse().map_err(|_| ParseError {
+                    line,
+                    message: format!("bad number: {}", n),
+                })
+            })
+            .collect()
+    }
+
+    fn interval(line: usize, start: u64, length: u64) -> Result<Interval, ParseError> {
+        let interval = (start as u128)..(start as u128 + length as u128);
+
+        if interval.end > DOMAIN.end {
+            return Err(ParseError {
+                line,
+                message: format!("{} + {} exceeds the domain", start, length),
+            });
+        }
+
+        Ok(interval)
+    }
+
     let seeds_pattern = regex!(r"seeds:\s*(?<numbers>.+)");
     let map_pattern = regex!(r"(?<source>\w+)-to-(?<destination>\w+) map:");
 
     let mut almanac = Almanac::default();
-    let mut current_map: Option<&mut Vec<Range>> = None;
+    let mut current_map: Option<Map> = None;
 
-    for line in lines {
+    for (index, line) in lines.into_iter().enumerate() {
         if line.is_empty() {
-            current_map = None;
+            if let Some(map) = current_map.take() {
+                almanac.insert(map)?;
+            }
+
             continue;
         }
 
         match current_map {
             Some(ref mut map) => {
-                let mut numbers = line
-                    .split_whitespace()
-                    .map(|n| u64::from_str_radix(n, 10).unwrap());
-
-                map.push(Range {
-                    destination: numbers.next().unwrap(),
-                    start: numbers.next().unwrap(),
-                    length: numbers.next().unwrap(),
+                let (destination, start, length) = match parse_numbers(index + 1, &line)?[..] {
+                    [destination, start, length] => (destination, start, length),
+                    _ => {
+                        return Err(ParseError {
+                            line: index + 1,
+                            message: "ranges must have three numbers".to_string(),
+                        })
+                    }
+                };
+
+                interval(index + 1, destination, length)?;
+                interval(index + 1, start, length)?;
+
+                map.ranges.push(Range {
+                    destination,
+                    start,
+                    length,
+                    line: index + 1,
                 });
             }
             None => {
                 if let Some(map_captures) = map_pattern.captures(&line) {
-                    let source = map_captures.name("source").unwrap().as_str();
-                    let destination = map_captures.name("destination").unwrap().as_str();
-
-                    current_map = Some(match (source, destination) {
-                        ("seed", "soil") => &mut almanac.seed_to_soil,
-                        ("soil", "fertilizer") => &mut almanac.soil_to_fertilizer,
-                        ("fertilizer", "water") => &mut almanac.fertilizer_to_water,
-                        ("water", "light") => &mut almanac.water_to_light,
-                        ("light", "temperature") => &mut almanac.light_to_temperature,
-                        ("temperature", "humidity") => &mut almanac.temperature_to_humidity,
-                        ("humidity", "location") => &mut almanac.humidity_to_location,
-                        _ => unreachable!(),
+                    current_map = Some(Map {
+                        source: map_captures.name("source").unwrap().as_str().to_string(),
+                        destination: map_captures
+                            .name("destination")
+                            .unwrap()
+                            .as_str()
+                            .to_string(),
+                        ranges: Vec::new(),
+                        by_source: RangeIndex::default(),
+                        by_destination: RangeIndex::default(),
+                        line: index + 1,
                     });
                 }
 
                 if let Some(seeds_captures) = seeds_pattern.captures(&line) {
-                    almanac.seeds = seeds_captures
-                        .name("numbers")
-                        .unwrap()
-                        .as_str()
-                        .split_whitespace()
-                        .map(|d| u64::from_str_radix(d, 10).unwrap())
-                        .collect();
+                    almanac.seeds =
+                        parse_numbers(index + 1, seeds_captures.name("numbers").unwrap().as_str())?;
+
+                    almanac.seed_ranges = almanac
+                        .seeds
+                        .chunks_exact(2)
+                        .map(|c| interval(index + 1, c[0], c[1]))
+                        .collect::<Result<_, _>>()?;
+                }
+            }
+        }
+    }
+
+    if let Some(map) = current_map {
+        almanac.insert(map)?;
+    }
+
+    Ok(almanac)
+}
+
+/// Finds likely mistakes in the almanac's maps: ranges whose sources or
+/// destinations overlap, ranges whose destinations can also be reached by
+/// numbers no range covers (so the map isn't injective), and gaps between
+/// ranges that leave numbers unmapped.
+fn lint(almanac: &Almanac) -> Vec<Lint> {
+    fn overlaps<F>(ranges: &[Range], interval: F, kind: &str, lints: &mut Vec<Lint>)
+    where
+        F: Fn(&Range) -> Interval,
+    {
+        // empty ranges cover no numbers, so they can't overlap anything:
+        let mut sorted = ranges.iter().filter(|r| r.length > 0).collect::<Vec<_>>();
+        sorted.sort_by_key(|r| interval(r).start);
+
+        for (i, a) in sorted.iter().enumerate() {
+            for b in sorted[(i + 1)..]
+                .iter()
+                .take_while(|b| interval(b).start < interval(a).end)
+            {
+                let (first, second) = if a.line < b.line { (a, b) } else { (b, a) };
+
+                lints.push(Lint {
+                    line: second.line,
+                    message: format!(
+                        "{} range {:?} overlaps line {}",
+                        kind,
+                        interval(second),
+                        first.line
+                    ),
+                });
+            }
+        }
+    }
+
+    let mut lints = Vec::new();
+
+    for map in almanac.maps.values().flatten() {
+        overlaps(&map.ranges, Range::source, "source", &mut lints);
+        overlaps(&map.ranges, Range::destination, "destination", &mut lints);
+
+        let sources = merge_intervals(map.ranges.iter().map(Range::source).collect());
+        let unmapped = complement(&sources);
+
+        for r in &map.ranges {
+            for u in &unmapped {
+                let overlap = r.destination().start.max(u.start)..r.destination().end.min(u.end);
+
+                if !overlap.is_empty() {
+                    lints.push(Lint {
+                        line: r.line,
+                        message: format!(
+                            "destination range {:?} is also reached by unmapped numbers {:?}",
+                            r.destination(),
+                            overlap
+                        ),
+                    });
                 }
             }
         }
+
+        for gap in sources.windows(2).map(|w| w[0].end..w[1].start) {
+            lints.push(Lint {
+                line: map.line,
+                message: format!(
+                    "{}-to-{} map leaves {:?} unmapped",
+                    map.source, map.destination, gap
+                ),
+            });
+        }
     }
 
-    almanac
+    lints.sort_by_key(|l| l.line);
+    lints
 }
 
-fn location_to_seed(location: u64, almanac: &Almanac) -> u64 {
-    let mut seed = location;
-    seed = destination_to_source(seed, &almanac.humidity_to_location);
-    seed = destination_to_source(seed, &almanac.temperature_to_humidity);
-    seed = destination_to_source(seed, &almanac.light_to_temperature);
-    seed = destination_to_source(seed, &almanac.water_to_light);
-    seed = destination_to_source(seed, &almanac.fertilizer_to_water);
-    seed = destination_to_source(seed, &almanac.soil_to_fertilizer);
-    seed = destination_to_source(seed, &almanac.seed_to_soil);
-    seed
+/// Returns the numbers in the domain that aren't in any of the provided
+/// sorted, merged intervals.
+fn complement(intervals: &[Interval]) -> Vec<Interval> {
+    let mut start = DOMAIN.start;
+    let mut gaps = Vec::new();
+
+    for interval in intervals {
+        gaps.push(start..interval.start);
+        start = interval.end;
+    }
+
+    gaps.push(start..DOMAIN.end);
+    gaps.retain(|g| !g.is_empty());
+    gaps
 }
 
-fn destination_to_source(n: u64, destination_map: &Vec<Range>) -> u64 {
-    destination_map
-        .iter()
-        .find(|&r| (r.destination..(r.destination + r.length)).contains(&n))
-        .map_or(n, |r| (n - r.destination) + r.start)
+/// Returns the lowest location that any of the provided seeds map to, given
+/// the maps from seed to location.
+fn min_location(seeds: &[Interval], path: &[&Map]) -> u64 {
+    path.iter()
+        .fold(seeds.to_vec(), |intervals, map| {
+            map_intervals(&intervals, &map.ranges)
+        })
+        .first()
+        .map_or(0, |i| i.start as u64)
+}
+
+/// Maps intervals of source numbers to intervals of destination numbers,
+/// splitting them wherever they cross the boundary of a range. The result is
+/// sorted, with overlapping and adjacent intervals merged.
+fn map_intervals(intervals: &[Interval], source_map: &[Range]) -> Vec<Interval> {
+    merge_intervals(
+        intervals
+            .iter()
+            .flat_map(|i| split(i.clone(), source_map))
+            .map(|piece| shift(&piece.source, piece.offset))
+            .collect(),
+    )
+}
+
+/// Splits an interval of source numbers wherever it crosses the boundary of a
+/// range, returning each piece along with the offset the map adds to it.
+fn split(interval: Interval, source_map: &[Range]) -> Vec<Piece> {
+    let mut unmapped = vec![interval];
+    let mut pieces = Vec::new();
+
+    for r in source_map {
+        let source = r.source();
+        let mut remaining = Vec::new();
+
+        for interval in unmapped {
+            let overlap = interval.start.max(source.start)..interval.end.min(source.end);
+
+            if overlap.is_empty() {
+                remaining.push(interval);
+                continue;
+            }
+
+            pieces.push(Piece {
+                source: overlap.clone(),
+                offset: r.destination as i128 - r.start as i128,
+            });
+
+            if interval.start < overlap.start {
+                remaining.push(interval.start..overlap.start);
+            }
+
+            if overlap.end < interval.end {
+                remaining.push(overlap.end..interval.end);
+            }
+        }
+
+        unmapped = remaining;
+    }
+
+    // numbers not covered by any range map to themselves:
+    pieces.extend(
+        unmapped
+            .into_iter()
+            .map(|source| Piece { source, offset: 0 }),
+    );
+    pieces
+}
+
+/// Adds `offset` to every number in an interval.
+fn shift(interval: &Interval, offset: i128) -> Interval {
+    ((interval.start as i128 + offset) as u128)..((interval.end as i128 + offset) as u128)
+}
+
+/// Sorts intervals, merging any that overlap or touch.
+fn merge_intervals(mut intervals: Vec<Interval>) -> Vec<Interval> {
+    intervals.retain(|i| !i.is_empty());
+    intervals.sort_by_key(|i| i.start);
+
+    let mut merged: Vec<Interval> = Vec::with_capacity(intervals.len());
+
+    for interval in intervals {
+        match merged.last_mut() {
+            Some(last) if interval.start <= last.end => last.end = last.end.max(interval.end),
+            _ => merged.push(interval),
+        }
+    }
+
+    merged
+}
+
+/// Returns the intervals of numbers in both of the provided sorted, merged
+/// sets of intervals.
+fn intersect(a: &[Interval], b: &[Interval]) -> Vec<Interval> {
+    let mut intersection = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    while i < a.len() && j < b.len() {
+        let overlap = a[i].start.max(b[j].start)..a[i].end.min(b[j].end);
+
+        if !overlap.is_empty() {
+            intersection.push(overlap);
+        }
+
+        if a[i].end < b[j].end {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    intersection
+}
+
+/// Returns every seed that maps to one of the provided locations, given the
+/// maps from seed to location.
+fn locations_to_seeds(locations: &[Interval], path: &[&Map]) -> Vec<Interval> {
+    path.iter()
+        .rev()
+        .fold(merge_intervals(locations.to_vec()), |intervals, map| {
+            destinations_to_sources(&intervals, map)
+        })
+}
+
+/// Maps sorted, merged intervals of destination numbers back to every
+/// interval of source numbers that converts into them. The result is sorted,
+/// with overlapping and adjacent intervals merged.
+fn destinations_to_sources(intervals: &[Interval], map: &Map) -> Vec<Interval> {
+    merge_intervals(
+        split(DOMAIN, &map.ranges)
+            .into_iter()
+            .flat_map(|piece| {
+                let image = shift(&piece.source, piece.offset);
+
+                intersect(&[image], intervals)
+                    .into_iter()
+                    .map(move |i| shift(&i, -piece.offset))
+            })
+            .collect(),
+    )
+}
+
+/// Returns the seed that maps to `location`, given the maps from seed to
+/// location.
+fn location_to_seed(location: u64, path: &[&Map]) -> u64 {
+    path.iter()
+        .rev()
+        .fold(location, |n, map| destination_to_source(n, map))
+}
+
+fn destination_to_source(n: u64, map: &Map) -> u64 {
+    // the range covers `n` and was checked to lie within the domain:
+    find_by_destination(n, map).map_or(n, |r| (n - r.destination) + r.start)
+}
+
+fn source_to_destination(n: u64, map: &Map) -> u64 {
+    // the range covers `n` and was checked to lie within the domain:
+    find_by_source(n, map).map_or(n, |r| (n - r.start) + r.destination)
+}
+
+/// Returns the range that converts `n` as a source number, if any.
+fn find_by_source(n: u64, map: &Map) -> Option<&Range> {
+    map.by_source.find(n).map(|i| &map.ranges[i])
+}
+
+/// Returns the range that converts to `n` as a destination number, if any.
+fn find_by_destination(n: u64, map: &Map) -> Option<&Range> {
+    map.by_destination.find(n).map(|i| &map.ranges[i])
+}
+
+/// Prints the number at every stage of a query's conversion, along with the
+/// line of the range responsible for each step.
+fn trace(query: &Query, from: &str, to: &str, path: &[&Map]) {
+    fn step(category: &str, n: u64, range: Option<&Range>) {
+        match range {
+            Some(r) => println!("  {} {} (line {})", category, n, r.line),
+            None => println!("  {} {} (no range)", category, n),
+        }
+    }
+
+    match *query {
+        Query::Forward(start) => {
+            println!("{} {}", from, start);
+
+            let mut n = start;
+
+            for map in path {
+                let range = find_by_source(n, map);
+                n = source_to_destination(n, map);
+                step(&map.destination, n, range);
+            }
+        }
+        Query::Backward(start) => {
+            println!("{} {}", to, start);
+
+            let mut n = start;
+
+            for map in path.iter().rev() {
+                let range = find_by_destination(n, map);
+                n = destination_to_source(n, map);
+                step(&map.source, n, range);
+            }
+        }
+    }
+}
+
+impl Almanac {
+    /// Adds a map to the almanac, indexing its ranges. Fails if the almanac
+    /// already has a map between the same categories.
+    fn insert(&mut self, mut map: Map) -> Result<(), ParseError> {
+        let maps = self.maps.entry(map.source.clone()).or_default();
+
+        if maps.iter().any(|m| m.destination == map.destination) {
+            return Err(ParseError {
+                line: map.line,
+                message: format!("more than one {}-to-{} map", map.source, map.destination),
+            });
+        }
+
+        map.by_source = RangeIndex::build(&map.ranges, Range::source);
+        map.by_destination = RangeIndex::build(&map.ranges, Range::destination);
+
+        maps.push(map);
+        Ok(())
+    }
+
+    /// Returns the maps that convert numbers in category `from` to numbers in
+    /// category `to`, in the order they apply. There must be exactly one such
+    /// path, and no cycles among the categories reachable from `from`.
+    fn path(&self, from: &str, to: &str) -> Result<Vec<&Map>, PathError> {
+        self.check_cycles(from)?;
+
+        let mut routes = HashMap::new();
+
+        match self.count_routes(from, to, &mut routes) {
+            0 => {
+                return Err(PathError::Missing {
+                    from: from.to_string(),
+                    to: to.to_string(),
+                })
+            }
+            1 => {}
+            _ => {
+                return Err(PathError::Ambiguous {
+                    from: from.to_string(),
+                    to: to.to_string(),
+                })
+            }
+        }
+
+        let mut path = Vec::new();
+        let mut category = from;
+
+        while category != to {
+            // exactly one route leads on from each category along the path:
+            let map = self.maps[category]
+                .iter()
+                .find(|m| routes.get(m.destination.as_str()) == Some(&1))
+                .unwrap();
+
+            path.push(map);
+            category = &map.destination;
+        }
+
+        Ok(path)
+    }
+
+    /// Returns the number of distinct routes from `category` to `to`,
+    /// remembering the count for every category visited.
+    fn count_routes<'a>(
+        &'a self,
+        category: &'a str,
+        to: &str,
+        routes: &mut HashMap<&'a str, usize>,
+    ) -> usize {
+        if let Some(&count) = routes.get(category) {
+            return count;
+        }
+
+        let count = if category == to {
+            1
+        } else {
+            self.maps
+                .get(category)
+                .into_iter()
+                .flatten()
+                .map(|m| self.count_routes(&m.destination, to, routes))
+                .fold(0, usize::saturating_add)
+        };
+
+        routes.insert(category, count);
+        count
+    }
+
+    /// Fails if any category reachable from `from` can be converted back to
+    /// itself.
+    fn check_cycles(&self, from: &str) -> Result<(), PathError> {
+        fn visit<'a>(
+            almanac: &'a Almanac,
+            category: &'a str,
+            visiting: &mut HashSet<&'a str>,
+            done: &mut HashSet<&'a str>,
+        ) -> Result<(), PathError> {
+            if done.contains(category) {
+                return Ok(());
+            }
+
+            if !visiting.insert(category) {
+                return Err(PathError::Cycle(category.to_string()));
+            }
+
+            for map in almanac.maps.get(category).into_iter().flatten() {
+                visit(almanac, &map.destination, visiting, done)?;
+            }
+
+            visiting.remove(category);
+            done.insert(category);
+            Ok(())
+        }
+
+        visit(self, from, &mut HashSet::new(), &mut HashSet::new())
+    }
+}
+
+impl Range {
+    /// Returns the source numbers the range covers.
+    fn source(&self) -> Interval {
+        (self.start as u128)..(self.start as u128 + self.length as u128)
+    }
+
+    /// Returns the destination numbers the range covers.
+    fn destination(&self) -> Interval {
+        (self.destination as u128)..(self.destination as u128 + self.length as u128)
+    }
+}
+
+impl RangeIndex {
+    /// Indexes ranges by the intervals `interval` returns for them.
+    fn build<F>(ranges: &[Range], interval: F) -> Self
+    where
+        F: Fn(&Range) -> Interval,
+    {
+        // empty ranges cover no numbers, and their end would sort before
+        // their start, leaving them covering everything after it:
+        let mut boundaries = ranges
+            .iter()
+            .enumerate()
+            .map(|(i, r)| (i, interval(r)))
+            .filter(|(_, interval)| !interval.is_empty())
+            .flat_map(|(i, interval)| [(interval.start, true, i), (interval.end, false, i)])
+            .collect::<Vec<_>>();
+
+        boundaries.sort_unstable();
+
+        // sweep across the boundaries, tracking which ranges cover the
+        // numbers between each pair:
+        let mut covering = BTreeSet::new();
+        let mut segments: Vec<(Interval, usize)> = Vec::new();
+        let mut previous = 0;
+
+        for (position, starts, i) in boundaries {
+            if let Some(&first) = covering.first() {
+                if previous < position {
+                    match segments.last_mut() {
+                        Some((last, r)) if last.end == previous && *r == first => {
+                            last.end = position
+                        }
+                        _ => segments.push((previous..position, first)),
+                    }
+                }
+            }
+
+            if starts {
+                covering.insert(i);
+            } else {
+                covering.remove(&i);
+            }
+
+            previous = position;
+        }
+
+        Self { segments }
+    }
+
+    /// Returns the index of the range covering `n`, if any.
+    fn find(&self, n: u64) -> Option<usize> {
+        let n = n as u128;
+        let i = self.segments.partition_point(|(s, _)| s.end <= n);
+
+        self.segments
+            .get(i)
+            .filter(|(s, _)| s.start <= n)
+            .map(|&(_, r)| r)
+    }
+}
+
+impl Composition {
+    /// Collapses a chain of maps into a single function.
+    fn compose(path: &[&Map]) -> Self {
+        let mut pieces = vec![Piece {
+            source: DOMAIN,
+            offset: 0,
+        }];
+
+        for map in path {
+            pieces = pieces
+                .into_iter()
+                .flat_map(|piece| {
+                    // split the piece's image, then carry the splits back to
+                    // its source:
+                    split(shift(&piece.source, piece.offset), &map.ranges)
+                        .into_iter()
+                        .map(move |p| Piece {
+                            source: shift(&p.source, -piece.offset),
+                            offset: piece.offset + p.offset,
+                        })
+                })
+                .collect();
+        }
+
+        pieces.sort_by_key(|p| p.source.start);
+
+        // join neighbouring pieces that apply the same offset:
+        let mut joined: Vec<Piece> = Vec::with_capacity(pieces.len());
+
+        for piece in pieces {
+            match joined.last_mut() {
+                Some(last)
+                    if last.source.end == piece.source.start && last.offset == piece.offset =>
+                {
+                    last.source.end = piece.source.end
+                }
+                _ => joined.push(piece),
+            }
+        }
+
+        Self { pieces: joined }
+    }
+
+    /// Prints the pieces as a JSON array of objects.
+    fn print_json(&self) {
+        let objects = self
+            .pieces
+            .iter()
+            .map(|p| {
+                format!(
+                    "{{\"start\":{},\"end\":{},\"offset\":{}}}",
+                    p.source.start, p.source.end, p.offset
+                )
+            })
+            .collect::<Vec<_>>();
+
+        println!("[{}]", objects.join(","));
+    }
+
+    /// Prints the pieces as a table, one row per piece.
+    fn print_table(&self) {
+        println!("{:>20} {:>20} {:>21}", "start", "end", "offset");
+
+        for p in &self.pieces {
+            println!(
+                "{:>20} {:>20} {:>+21}",
+                p.source.start, p.source.end, p.offset
+            );
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Display for PathError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            PathError::Missing { from, to } => write!(f, "no way to convert {} to {}", from, to),
+            PathError::Ambiguous { from, to } => {
+                write!(f, "more than one way to convert {} to {}", from, to)
+            }
+            PathError::Cycle(category) => write!(f, "maps form a cycle through {}", category),
+        }
+    }
 }
@@ -1,15 +1,108 @@
-use std::{io::stdin, iter::zip};
+use num_bigint::BigUint;
+use std::{env::args, io::stdin, iter::zip, ops};
 
 #[derive(Default)]
 struct Race {
-    time: u64,
-    distance: u64,
+    time: BigUint,
+    distance: BigUint,
 }
 
+/// A way for a boat to move once its button is released.
+trait RaceModel {
+    /// Returns how far the boat travels when its button is held for `hold`
+    /// milliseconds of a race lasting `time`.
+    fn distance(&self, hold: &BigUint, time: &BigUint) -> BigUint;
+
+    /// Returns a hold time that travels furthest, if one can be found without
+    /// trying them all. Distances must never fall before it or rise after it.
+    fn peak(&self, _time: &BigUint) -> Option<BigUint> {
+        None
+    }
+
+    /// Returns the number of hold times that beat the record distance. Hold
+    /// times either side of the peak are binary searched, or every hold time
+    /// is tried if there's no peak.
+    fn ways_to_win(&self, race: &Race) -> BigUint {
+        match self.peak(&race.time) {
+            Some(peak) => {
+                let window = winning_window(self, race, peak);
+                window.end - window.start
+            }
+            None => ways_to_win_by_brute_force(self, race),
+        }
+    }
+}
+
+/// Speed equals hold time, and the boat keeps its speed to the end.
+struct Linear;
+
+/// Speed equals hold time up to a top speed.
+struct Capped {
+    top: BigUint,
+}
+
+/// Speed equals the square of the hold time.
+struct Quadratic;
+
+/// Speed equals hold time, but the boat slows by one every `every`
+/// milliseconds after it's released.
+struct Drag {
+    every: BigUint,
+}
+
+enum Output {
+    Answers,
+    Report,
+}
+
+struct Options {
+    brute_force: bool,
+    model: Box<dyn RaceModel>,
+    output: Output,
+}
+
+/// The number of columns in a race plot.
+const PLOT_WIDTH: u32 = 60;
+
+/// The number of rows in a race plot, not counting the one for zero.
+const PLOT_HEIGHT: u32 = 10;
+
 fn main() {
-    // part 1
+    let options = parse_args(args().skip(1));
+    let model = options.model.as_ref();
+
+    // counting every hold time is slow, but useful for checking the closed
+    // forms:
+    let ways_to_win = |race: &Race| {
+        if options.brute_force {
+            ways_to_win_by_brute_force(model, race)
+        } else {
+            model.ways_to_win(race)
+        }
+    };
+
     let races = parse_races(stdin().lines().map(|l| l.unwrap()));
-    let ways_product = races.iter().map(|r| ways_to_win(&r)).fold(1, |p, c| p * c);
+
+    if let Output::Report = options.output {
+        for (i, race) in races.iter().enumerate() {
+            if i > 0 {
+                println!();
+            }
+
+            println!(
+                "race {}: {} ms, record {} mm",
+                i + 1,
+                race.time,
+                race.distance
+            );
+            print_report(model, race);
+        }
+
+        return;
+    }
+
+    // part 1
+    let ways_product = races.iter().map(ways_to_win).product::<BigUint>();
 
     // part 2
     let megarace = combine_races(&races);
@@ -19,6 +112,63 @@ fn main() {
     println!("{}", ways_megarace);
 }
 
+/// Reads whether to count ways to win by brute force (`--brute-force`), and
+/// how boats move (`--model linear|capped:<top>|quadratic|drag:<every>`,
+/// linear by default). It can also print a strategy report with a plot for
+/// each race (`--report`) instead of the answers.
+fn parse_args<I>(args: I) -> Options
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut brute_force = false;
+    let mut model: Box<dyn RaceModel> = Box::new(Linear);
+    let mut output = Output::Answers;
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--brute-force" => brute_force = true,
+            "--model" => model = parse_model(&args.next().expect("missing model")),
+            "--report" => output = Output::Report,
+            _ => panic!("unknown argument: {}", arg),
+        }
+    }
+
+    Options {
+        brute_force,
+        model,
+        output,
+    }
+}
+
+fn parse_model(spec: &str) -> Box<dyn RaceModel> {
+    fn parse_parameter(str: &str) -> BigUint {
+        str.trim()
+            .parse()
+            .unwrap_or_else(|_| panic!("bad model parameter: {}", str))
+    }
+
+    let (kind, parameter) = spec.split_once(':').unwrap_or((spec, ""));
+
+    match (kind, parameter) {
+        ("linear", "") => Box::new(Linear),
+        ("capped", _) => Box::new(Capped {
+            top: parse_parameter(parameter),
+        }),
+        ("quadratic", "") => Box::new(Quadratic),
+        ("drag", _) => {
+            let every = parse_parameter(parameter);
+
+            if every == BigUint::ZERO {
+                panic!("drag must slow the boat every 1 or more milliseconds");
+            }
+
+            Box::new(Drag { every })
+        }
+        _ => panic!("unknown model: {}", spec),
+    }
+}
+
 fn parse_races<I>(lines: I) -> Vec<Race>
 where
     I: IntoIterator<Item = String>,
@@ -26,7 +176,7 @@ where
     let mut rows = lines.into_iter().map(|l| {
         l.split_whitespace()
             .skip(1)
-            .map(|s| u64::from_str_radix(s, 10).unwrap())
+            .map(|s| s.parse::<BigUint>().unwrap())
             .collect::<Vec<_>>()
     });
 
@@ -38,10 +188,12 @@ where
         .collect()
 }
 
-fn combine_races(races: &Vec<Race>) -> Race {
-    fn concat<I>(numbers: I) -> u64
+/// Combines the races into one by concatenating the digits of their times
+/// and distances.
+fn combine_races(races: &[Race]) -> Race {
+    fn concat<'a, I>(numbers: I) -> BigUint
     where
-        I: Iterator<Item = u64>,
+        I: Iterator<Item = &'a BigUint>,
     {
         numbers
             .map(|n| n.to_string())
@@ -52,13 +204,321 @@ fn combine_races(races: &Vec<Race>) -> Race {
     }
 
     Race {
-        time: concat(races.iter().map(|r| r.time)),
-        distance: concat(races.iter().map(|r| r.distance)),
+        time: concat(races.iter().map(|r| &r.time)),
+        distance: concat(races.iter().map(|r| &r.distance)),
     }
 }
 
-fn ways_to_win(race: &Race) -> u64 {
-    (0..race.time)
-        .filter(|&t| ((race.time - t) * t) > race.distance)
-        .count() as _
+impl RaceModel for Linear {
+    fn distance(&self, hold: &BigUint, time: &BigUint) -> BigUint {
+        hold * (time - hold)
+    }
+
+    fn peak(&self, time: &BigUint) -> Option<BigUint> {
+        Some(time / 2u32)
+    }
+
+    fn ways_to_win(&self, race: &Race) -> BigUint {
+        match first_linear_win(race) {
+            Some(first) => &race.time - first * 2u32 + 1u32,
+            None => BigUint::ZERO,
+        }
+    }
+}
+
+impl RaceModel for Capped {
+    fn distance(&self, hold: &BigUint, time: &BigUint) -> BigUint {
+        hold.min(&self.top) * (time - hold)
+    }
+
+    fn peak(&self, time: &BigUint) -> Option<BigUint> {
+        Some((time / 2u32).min(self.top.clone()))
+    }
+
+    /// Below the top speed, the winning hold times start where they would
+    /// for a linear model. If the top speed is reached first, they end when
+    /// `top * (T - t)` no longer beats the record.
+    fn ways_to_win(&self, race: &Race) -> BigUint {
+        let first = match first_linear_win(race) {
+            Some(first) if first <= self.top => first,
+            _ => return BigUint::ZERO,
+        };
+
+        let last = (&race.time - &first).min(&race.time - &race.distance / &self.top - 1u32);
+
+        last - first + 1u32
+    }
+}
+
+impl RaceModel for Quadratic {
+    fn distance(&self, hold: &BigUint, time: &BigUint) -> BigUint {
+        hold * hold * (time - hold)
+    }
+
+    /// `t² * (T - t)` rises until `2T / 3`, so the peak is one of the hold
+    /// times either side of it.
+    fn peak(&self, time: &BigUint) -> Option<BigUint> {
+        let below = time * 2u32 / 3u32;
+        let above = &below + 1u32;
+
+        if above < *time && self.distance(&above, time) > self.distance(&below, time) {
+            Some(above)
+        } else {
+            Some(below)
+        }
+    }
+}
+
+impl RaceModel for Drag {
+    /// The boat spends `every` milliseconds at each speed from the hold time
+    /// down, until it either stops or runs out of time.
+    fn distance(&self, hold: &BigUint, time: &BigUint) -> BigUint {
+        let moving = time - hold;
+        let slowdowns = (&moving / &self.every).min(hold.clone());
+
+        // the speeds from `hold` down to `hold - slowdowns + 1`, then whatever
+        // time is left at the speed after:
+        let sum = &slowdowns * (hold * 2u32 + 1u32 - &slowdowns) / 2u32;
+        let rest = (moving - &slowdowns * &self.every) * (hold - &slowdowns);
+
+        sum * &self.every + rest
+    }
+
+    /// Holding longer gains less and less, so the peak is the first hold time
+    /// where holding one more doesn't gain anything.
+    fn peak(&self, time: &BigUint) -> Option<BigUint> {
+        Some(partition_point(BigUint::ZERO, time.clone(), |t| {
+            self.distance(&(t + 1u32), time) > self.distance(t, time)
+        }))
+    }
+}
+
+/// Returns the first hold time that beats the record distance if speed equals
+/// hold time, or `None` if none do.
+///
+/// Holding for `t` wins when `t * (T - t) > D`, so the winning hold times lie
+/// strictly between the roots of `t² - Tt + D`, and they're symmetric around
+/// `T / 2`. The lower root is found with an integer square root, then nudged
+/// up to the first hold time that actually wins.
+fn first_linear_win(race: &Race) -> Option<BigUint> {
+    let time = &race.time;
+    let distance = &race.distance;
+    let half = time / 2u32;
+
+    let wins = |t: &BigUint| t * (time - t) > *distance;
+
+    let squared = time * time;
+    let product = distance * 4u32;
+
+    if squared <= product {
+        return None;
+    }
+
+    // (T - √d) / 2 is never more than half a step above the lower root, so at
+    // most one or two nudges are needed:
+    let mut first = (time - (squared - product).sqrt()) / 2u32;
+
+    while first <= half && !wins(&first) {
+        first += 1u32;
+    }
+
+    (first <= half).then_some(first)
+}
+
+/// Prints the best hold time for a race, the hold times that win, and a plot
+/// of distance against hold time with the record drawn across it.
+fn print_report<M>(model: &M, race: &Race)
+where
+    M: RaceModel + ?Sized,
+{
+    let best = best_hold(model, &race.time);
+    let furthest = model.distance(&best, &race.time);
+
+    if furthest > race.distance {
+        let margin = &furthest - &race.distance;
+        println!(
+            "  best hold: {} ms, reaching {} mm ({} over the record)",
+            best, furthest, margin
+        );
+    } else if furthest == race.distance {
+        println!(
+            "  best hold: {} ms, reaching {} mm (matching the record)",
+            best, furthest
+        );
+    } else {
+        let margin = &race.distance - &furthest;
+        println!(
+            "  best hold: {} ms, reaching {} mm ({} short of the record)",
+            best, furthest, margin
+        );
+    }
+
+    let window = winning_window(model, race, best);
+
+    if window.is_empty() {
+        println!("  winning holds: none");
+    } else {
+        let ways = &window.end - &window.start;
+        println!(
+            "  winning holds: {}..={} ms ({} ways)",
+            window.start,
+            window.end - 1u32,
+            ways
+        );
+    }
+
+    println!();
+    print_plot(model, race);
+}
+
+/// Prints distance against hold time, sampling at most `PLOT_WIDTH` hold
+/// times. Distances are marked with `*` and the record with `-`.
+fn print_plot<M>(model: &M, race: &Race)
+where
+    M: RaceModel + ?Sized,
+{
+    let columns = (&race.time + 1u32).min(BigUint::from(PLOT_WIDTH));
+
+    let holds = (0..PLOT_WIDTH)
+        .map(BigUint::from)
+        .take_while(|c| *c < columns)
+        .map(|c| match &columns - 1u32 {
+            last if last == BigUint::ZERO => last,
+            last => &race.time * c / last,
+        })
+        .collect::<Vec<_>>();
+
+    let distances = holds
+        .iter()
+        .map(|h| model.distance(h, &race.time))
+        .collect::<Vec<_>>();
+
+    let top = distances
+        .iter()
+        .chain([&race.distance])
+        .max()
+        .unwrap()
+        .max(&BigUint::from(1u32))
+        .clone();
+
+    let row = |d: &BigUint| u32::try_from(d * PLOT_HEIGHT / &top).unwrap();
+    let record = row(&race.distance);
+    let width = top.to_string().len();
+
+    for r in (0..=PLOT_HEIGHT).rev() {
+        let label = if r == record {
+            race.distance.to_string()
+        } else if r == PLOT_HEIGHT {
+            top.to_string()
+        } else if r == 0 {
+            "0".to_string()
+        } else {
+            String::new()
+        };
+
+        let line = distances
+            .iter()
+            .map(|d| match row(d) {
+                d if d == r => '*',
+                _ if r == record => '-',
+                _ => ' ',
+            })
+            .collect::<String>();
+
+        println!("  {:>width$} |{}", label, line.trim_end());
+    }
+
+    println!("  {:>width$} +{}", "", "-".repeat(holds.len()));
+
+    if holds.len() == 1 {
+        println!("  {:>width$}  0", "");
+    } else {
+        // keep the labels apart when there are only a few columns:
+        let pad = (holds.len() - 1).max(race.time.to_string().len() + 1);
+        println!("  {:>width$}  0{:>pad$}", "", race.time);
+    }
+}
+
+/// Returns a hold time that travels furthest, trying every one if the model
+/// has no peak.
+fn best_hold<M>(model: &M, time: &BigUint) -> BigUint
+where
+    M: RaceModel + ?Sized,
+{
+    if let Some(peak) = model.peak(time) {
+        return peak;
+    }
+
+    let mut hold = BigUint::ZERO;
+    let mut best = (BigUint::ZERO, BigUint::ZERO);
+
+    while hold < *time {
+        let distance = model.distance(&hold, time);
+
+        if distance > best.1 {
+            best = (hold.clone(), distance);
+        }
+
+        hold += 1u32;
+    }
+
+    best.0
+}
+
+/// Returns the hold times that beat the record distance, binary searching
+/// either side of a hold time that travels furthest.
+fn winning_window<M>(model: &M, race: &Race, peak: BigUint) -> ops::Range<BigUint>
+where
+    M: RaceModel + ?Sized,
+{
+    let wins = |t: &BigUint| model.distance(t, &race.time) > race.distance;
+
+    if peak >= race.time || !wins(&peak) {
+        return peak.clone()..peak;
+    }
+
+    let first = partition_point(BigUint::ZERO, peak.clone(), |t| !wins(t));
+    let last = partition_point(peak, race.time.clone(), wins);
+
+    first..last
+}
+
+/// Returns the first number in `low..high` that `predicate` is false for, or
+/// `high` if there isn't one. `predicate` must be true for every number
+/// before it.
+fn partition_point<P>(mut low: BigUint, mut high: BigUint, predicate: P) -> BigUint
+where
+    P: Fn(&BigUint) -> bool,
+{
+    while low < high {
+        let middle = &low + (&high - &low) / 2u32;
+
+        if predicate(&middle) {
+            low = middle + 1u32;
+        } else {
+            high = middle;
+        }
+    }
+
+    low
+}
+
+/// Returns the number of hold times that beat the record distance by trying
+/// every one of them.
+fn ways_to_win_by_brute_force<M>(model: &M, race: &Race) -> BigUint
+where
+    M: RaceModel + ?Sized,
+{
+    let mut hold = BigUint::ZERO;
+    let mut ways = BigUint::ZERO;
+
+    while hold < race.time {
+        if model.distance(&hold, &race.time) > race.distance {
+            ways += 1u32;
+        }
+
+        hold += 1u32;
+    }
+
+    ways
 }